@@ -0,0 +1,479 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, BufRead, BufReader, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use filmed::{IMDbLookup, RatedTitle};
+use serde::Serialize;
+
+use crate::Args;
+
+const IMDBV3_HEADER: [&str; 4] = ["Const", "Title", "Year", "Your Rating"];
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExportFormat {
+    /// IMDb v3 CSV, importable by TMDB, Letterboxd, ...
+    Imdb,
+    /// JSON Lines, one title per line
+    Json,
+}
+
+impl ExportFormat {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Imdb => "csv",
+            Self::Json => "jsonl",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitBy {
+    Rating,
+    Type,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TitleType {
+    Movie,
+    Show,
+}
+
+/// Files titles are exported to, each written in every requested format. When written to a
+/// directory, titles are split into generic (rated), want2see (not rated) and favorited (favorited,
+/// also in generic). When split by type, generic is replaced by movies and shows. When streamed,
+/// every title ends up in one stream. When only favorited titles are exported, they end up in
+/// favorited alone
+pub struct ExportFiles {
+    /// Rated movies, or all rated titles unless split by type
+    generic: Vec<Sink>,
+    split: Option<SplitFiles>,
+}
+
+struct SplitFiles {
+    shows: Option<Vec<Sink>>,
+    want2see: Vec<Sink>,
+    favorited: Vec<Sink>,
+}
+
+impl ExportFiles {
+    /// Checks that titles can be exported with given options, and creates --output directory. No
+    /// file is created or truncated yet
+    pub fn check(args: &Args) -> io::Result<()> {
+        Self::formats(args)?;
+        if args.output != Path::new("-") {
+            fs::create_dir_all(&args.output)?;
+        }
+        Ok(())
+    }
+
+    /// Creates export files in --output directory, or a stdout stream if it's "-"
+    pub fn new(args: &Args) -> io::Result<Self> {
+        let formats = Self::formats(args)?;
+        let output = args.output.as_path();
+        if output == Path::new("-") {
+            return Ok(Self {
+                generic: vec![Sink::new(Box::new(stdout()), formats[0], true, HashSet::new())?],
+                split: None,
+            });
+        }
+
+        fs::create_dir_all(output)?;
+        let open = |name: &str| -> io::Result<Vec<Sink>> {
+            formats
+                .iter()
+                .map(|format| {
+                    Sink::open(
+                        &output.join(name).with_extension(format.extension()),
+                        *format,
+                        args.append,
+                    )
+                })
+                .collect()
+        };
+        if args.only_favorites {
+            return Ok(Self {
+                generic: open("favorited")?,
+                split: None,
+            });
+        }
+        let (generic, shows) = match args.split_by.unwrap_or(SplitBy::Rating) {
+            SplitBy::Rating => (open("generic")?, None),
+            SplitBy::Type => (open("movies")?, Some(open("shows")?)),
+        };
+        Ok(Self {
+            generic,
+            split: Some(SplitFiles {
+                shows,
+                want2see: open("want2see")?,
+                favorited: open("favorited")?,
+            }),
+        })
+    }
+
+    /// Requested formats without duplicates, if they can be written to --output
+    fn formats(args: &Args) -> io::Result<Vec<ExportFormat>> {
+        let mut formats = args.format.clone();
+        formats.sort_unstable();
+        formats.dedup();
+        if args.output == Path::new("-") {
+            if args.split_by.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--split-by can't be used when streaming to stdout",
+                ));
+            }
+            if formats.len() != 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only a single --format can be streamed to stdout",
+                ));
+            }
+        }
+        Ok(formats)
+    }
+
+    /// Writes a title matched at IMDb to the files it belongs to. Titles without IMDb data are skipped.
+    /// `title_type` is needed only when split by type
+    pub fn write_title<T: RatedTitle + IMDbLookup>(
+        &mut self,
+        title: &T,
+        title_type: Option<TitleType>,
+    ) -> io::Result<()> {
+        let Some(imdb_data) = title.imdb_data() else {
+            return Ok(());
+        };
+        let exported = ExportedTitle {
+            imdb_id: imdb_data.id.to_string(),
+            title: imdb_data.title().to_string(),
+            year: title.year().to_string(),
+            rating: title.rating().map(|rating| rating.to_string()),
+            favorited: title.is_favorited(),
+        };
+        self.write(&exported, title_type)
+    }
+
+    fn write(&mut self, title: &ExportedTitle, title_type: Option<TitleType>) -> io::Result<()> {
+        let Some(split) = self.split.as_mut() else {
+            return write_all(&mut self.generic, title);
+        };
+        if title.rating.is_none() {
+            return write_all(&mut split.want2see, title);
+        }
+        match (split.shows.as_mut(), title_type) {
+            (Some(shows), Some(TitleType::Show)) => write_all(shows, title)?,
+            _ => write_all(&mut self.generic, title)?,
+        }
+        if title.favorited {
+            write_all(&mut split.favorited, title)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut sinks = self.generic.iter_mut().collect::<Vec<_>>();
+        if let Some(split) = self.split.as_mut() {
+            sinks.extend(split.shows.iter_mut().flatten());
+            sinks.extend(split.want2see.iter_mut().chain(split.favorited.iter_mut()));
+        }
+        sinks.into_iter().try_for_each(Sink::flush)
+    }
+}
+
+/// Title as it's written to export files. Year and rating are kept as displayed by Filmweb, since
+/// shows' years may be ranges
+#[derive(Serialize)]
+struct ExportedTitle {
+    imdb_id: String,
+    title: String,
+    year: String,
+    rating: Option<String>,
+    favorited: bool,
+}
+
+impl ExportedTitle {
+    fn imdbv3_record(&self) -> [String; 4] {
+        [
+            self.imdb_id.clone(),
+            self.title.clone(),
+            self.year.clone(),
+            self.rating.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+fn write_all(sinks: &mut [Sink], title: &ExportedTitle) -> io::Result<()> {
+    sinks.iter_mut().try_for_each(|sink| sink.write(title))
+}
+
+/// A file or a stream in one of the formats. Titles already written to it are skipped, judging by
+/// their IMDb ID
+struct Sink {
+    writer: SinkWriter,
+    written: HashSet<String>,
+}
+
+enum SinkWriter {
+    Imdb(csv::Writer<Box<dyn Write>>),
+    Json(Box<dyn Write>),
+}
+
+impl Sink {
+    fn new(
+        writer: Box<dyn Write>,
+        format: ExportFormat,
+        write_header: bool,
+        written: HashSet<String>,
+    ) -> io::Result<Self> {
+        let writer = match format {
+            ExportFormat::Imdb => {
+                let mut writer = csv::Writer::from_writer(writer);
+                if write_header {
+                    writer.write_record(IMDBV3_HEADER)?;
+                }
+                SinkWriter::Imdb(writer)
+            }
+            ExportFormat::Json => SinkWriter::Json(writer),
+        };
+        Ok(Self { writer, written })
+    }
+
+    /// Opens a file. If `append` is set, titles already present in the file are remembered, and CSV
+    /// header is written only if the file is empty
+    fn open(path: &Path, format: ExportFormat, append: bool) -> io::Result<Self> {
+        if !append {
+            return Self::new(Box::new(File::create(path)?), format, true, HashSet::new());
+        }
+        let written = read_imdb_ids(path, format)?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let write_header = file.metadata()?.len() == 0;
+        Self::new(Box::new(file), format, write_header, written)
+    }
+
+    fn write(&mut self, title: &ExportedTitle) -> io::Result<()> {
+        if !self.written.insert(title.imdb_id.clone()) {
+            return Ok(());
+        }
+        match &mut self.writer {
+            SinkWriter::Imdb(writer) => writer.write_record(title.imdbv3_record())?,
+            SinkWriter::Json(writer) => {
+                serde_json::to_writer(&mut *writer, title)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            SinkWriter::Imdb(writer) => writer.flush(),
+            SinkWriter::Json(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Reads IMDb IDs of titles in an existing file
+fn read_imdb_ids(path: &Path, format: ExportFormat) -> io::Result<HashSet<String>> {
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    match format {
+        ExportFormat::Imdb => csv::Reader::from_path(path)?
+            .records()
+            .map(|record| -> io::Result<String> { Ok(record?.get(0).unwrap_or_default().to_string()) })
+            .collect(),
+        ExportFormat::Json => BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| -> io::Result<String> {
+                let title: serde_json::Value = serde_json::from_str(&line?)?;
+                Ok(title["imdb_id"].as_str().unwrap_or_default().to_string())
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Path in the temp directory, unique to the test run, with nothing there yet
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("filmweb-export-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn exported(imdb_id: &str) -> ExportedTitle {
+        ExportedTitle {
+            imdb_id: imdb_id.to_string(),
+            title: format!("Title, {imdb_id}"),
+            year: "2000".to_string(),
+            rating: Some("7".to_string()),
+            favorited: false,
+        }
+    }
+
+    fn ids<const N: usize>(ids: [&str; N]) -> HashSet<String> {
+        ids.into_iter().map(String::from).collect()
+    }
+
+    fn write_to(path: &Path, format: ExportFormat, append: bool, imdb_ids: &[&str]) {
+        let mut sink = Sink::open(path, format, append).unwrap();
+        for imdb_id in imdb_ids {
+            sink.write(&exported(imdb_id)).unwrap();
+        }
+        sink.flush().unwrap();
+    }
+
+    fn memory_sinks() -> Vec<Sink> {
+        vec![Sink::new(Box::new(io::sink()), ExportFormat::Imdb, true, HashSet::new()).unwrap()]
+    }
+
+    /// Export files the way `ExportFiles::new` lays them out, writing to memory
+    fn memory_files(split_by: Option<SplitBy>, streamed: bool) -> ExportFiles {
+        let split = (!streamed).then(|| SplitFiles {
+            shows: (split_by == Some(SplitBy::Type)).then(memory_sinks),
+            want2see: memory_sinks(),
+            favorited: memory_sinks(),
+        });
+        ExportFiles {
+            generic: memory_sinks(),
+            split,
+        }
+    }
+
+    /// Names of the files a title has been written to
+    fn files_written_to(files: &ExportFiles, imdb_id: &str) -> Vec<&'static str> {
+        let generic_name = match &files.split {
+            None => "stream",
+            Some(SplitFiles { shows: Some(_), .. }) => "movies",
+            Some(_) => "generic",
+        };
+        let mut sinks = vec![(generic_name, &files.generic)];
+        if let Some(split) = &files.split {
+            sinks.extend(split.shows.as_ref().map(|shows| ("shows", shows)));
+            sinks.push(("want2see", &split.want2see));
+            sinks.push(("favorited", &split.favorited));
+        }
+        sinks
+            .into_iter()
+            .filter(|(_, file)| file.iter().all(|sink| sink.written.contains(imdb_id)))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn titles_are_routed_to_their_files() {
+        let rated = Some("7");
+        let unrated = None;
+        let movie = Some(TitleType::Movie);
+        let show = Some(TitleType::Show);
+        let by_rating = Some(SplitBy::Rating);
+        let by_type = Some(SplitBy::Type);
+        #[rustfmt::skip]
+        let cases = [
+            // rating, favorited, title type, --split-by, streamed, expected files
+            (rated, false, movie, None, false, "generic"),
+            (rated, false, show, by_rating, false, "generic"),
+            (rated, false, movie, by_type, false, "movies"),
+            (rated, false, show, by_type, false, "shows"),
+            (unrated, false, None, by_rating, false, "want2see"),
+            (unrated, false, None, by_type, false, "want2see"),
+            (rated, true, movie, by_rating, false, "generic favorited"),
+            (rated, true, show, by_rating, false, "generic favorited"),
+            (rated, true, movie, by_type, false, "movies favorited"),
+            (rated, true, show, by_type, false, "shows favorited"),
+            (rated, false, show, None, true, "stream"),
+            (unrated, false, None, None, true, "stream"),
+            (rated, true, movie, None, true, "stream"),
+        ];
+        for (rating, favorited, title_type, split_by, streamed, expected) in cases {
+            let mut files = memory_files(split_by, streamed);
+            let title = ExportedTitle {
+                rating: rating.map(String::from),
+                favorited,
+                ..exported("tt1")
+            };
+            files.write(&title, title_type).unwrap();
+            assert_eq!(
+                files_written_to(&files, "tt1").join(" "),
+                expected,
+                "rating: {rating:?}, favorited: {favorited}, {title_type:?}, split by {split_by:?}, streamed: {streamed}"
+            );
+        }
+    }
+
+    #[test]
+    fn append_writes_a_single_header_and_no_duplicates() {
+        for format in [ExportFormat::Imdb, ExportFormat::Json] {
+            let path = temp_path(&format!("append.{}", format.extension()));
+            write_to(&path, format, false, "tt1 tt2");
+            write_to(&path, format, true, &["tt2", "tt3", "tt3"]);
+
+            let content = fs::read_to_string(&path).unwrap();
+            let headers_count = content.lines().filter(|line| line.starts_with("Const,")).count();
+            let expected_headers_count = usize::from(format == ExportFormat::Imdb);
+            assert_eq!(headers_count, expected_headers_count, "{format:?}");
+            assert_eq!(content.lines().count(), expected_headers_count + 3, "{format:?}");
+            assert_eq!(
+                read_imdb_ids(&path, format).unwrap(),
+                ids(["tt1", "tt2", "tt3"]),
+                "{format:?}"
+            );
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn append_to_missing_file_writes_header() {
+        let path = temp_path("append-missing.csv");
+        write_to(&path, ExportFormat::Imdb, true, "tt1");
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().next(), Some("Const,Title,Year,Your Rating"));
+        assert_eq!(content.lines().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_imdb_ids_from_csv() {
+        let path = temp_path("read.csv");
+        fs::write(
+            &path,
+            "Const,Title,Year,Your Rating\ntt1,\"A, B\",2000,7\ntt2,C,2001-2003,\n",
+        )
+        .unwrap();
+
+        assert_eq!(read_imdb_ids(&path, ExportFormat::Imdb).unwrap(), ids(["tt1", "tt2"]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_imdb_ids_from_jsonl() {
+        let path = temp_path("read.jsonl");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"imdb_id":"tt1","title":"A","year":"2000","rating":"7","favorited":true}"#,
+                "\n\n",
+                r#"{"imdb_id":"tt2","title":"B","year":"2001","rating":null,"favorited":false}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(read_imdb_ids(&path, ExportFormat::Json).unwrap(), ids(["tt1", "tt2"]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_imdb_ids_from_missing_file() {
+        let path = temp_path("read-missing.csv");
+
+        assert!(read_imdb_ids(&path, ExportFormat::Imdb).unwrap().is_empty());
+    }
+}
@@ -1,36 +1,49 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 use flume::Sender;
 use lazy_static::lazy_static;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use std::fmt::Display;
-use std::io::{stdin, stdout, Write};
+use std::io::{stderr, stdin, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use filmed::filmweb::auth::{ExportFiles, FilmwebUser, RatedPage, UserPage, UserPageType};
+use filmed::filmweb::auth::{FilmwebUser, RatedPage, UserPage, UserPageType};
 use filmed::imdb::IMDb;
 use filmed::{IMDbLookup, RatedTitle, Title, TitleID, User};
 
+use export::{ExportFiles, ExportFormat, SplitBy, TitleType};
+
+mod export;
+
 #[derive(Parser, Debug)]
 #[command(name = "filmweb-export")]
 #[command(author = "Remigiusz Micielski <remigiusz.micielski@gmail.com>")]
 #[command(version = "0.2.2")]
 #[command(about = "Exports user data from filmweb.pl to IMDBv3 csv file format", long_about = None)]
 struct Args {
-    #[arg(short, long, value_parser)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, value_parser, global = true)]
     username: Option<String>,
 
     /// _fwuser_token cookie value
-    #[arg(short, long, value_parser)]
+    #[arg(short, long, value_parser, global = true)]
     token: Option<String>,
 
     /// _fwuser_sessionId cookie value
-    #[arg(short, long, value_parser)]
+    #[arg(short, long, value_parser, global = true)]
     session: Option<String>,
 
     /// JWT cookie value
-    #[arg(short, long, value_parser)]
+    #[arg(short, long, value_parser, global = true)]
     jwt: Option<String>,
 
     /// Number of threads to spawn
@@ -40,46 +53,180 @@ struct Args {
     /// If enabled, successfully exported titles won't be printed
     #[arg(short, long, value_parser, default_value_t = false)]
     quiet: bool,
+
+    /// If enabled, IMDb matches are accepted only if both year and duration agree, without asking.
+    /// Anything else is treated as not found
+    #[arg(long, value_parser, default_value_t = false)]
+    imdb_only_verified: bool,
+
+    /// Directory to write files to, or "-" to stream a single format to stdout
+    #[arg(short, long, value_parser, default_value = "exports")]
+    output: PathBuf,
+
+    /// Format to export titles in, may be given multiple times to write all of them in one run
+    #[arg(short, long, value_enum, default_values_t = [ExportFormat::Imdb])]
+    format: Vec<ExportFormat>,
+
+    /// How rated titles are split into files: by rating (generic and favorited, the default) or by
+    /// type (movies, shows and favorited). Titles marked to see always go to want2see. Can't be used
+    /// with --only-favorites or when streaming to stdout
+    #[arg(long, value_enum, conflicts_with = "only_favorites")]
+    split_by: Option<SplitBy>,
+
+    /// If enabled, titles will be appended to existing CSV files instead of overwriting them. Titles
+    /// already present in a file won't be written again
+    #[arg(short, long, value_parser, default_value_t = false)]
+    append: bool,
+
+    /// Maximum number of pages to scrape per category, regardless of what Filmweb reports
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    max_pages: Option<u16>,
+
+    /// If enabled, pages and titles will be requested in random order. With --auto-paginate, pages are
+    /// shuffled only within batches of --threads pages, and categories are scraped one after another
+    #[arg(long, value_parser, default_value_t = false)]
+    shuffle: bool,
+
+    /// Seed for --shuffle, so the order can be reproduced
+    #[arg(long, value_parser, requires = "shuffle")]
+    seed: Option<u64>,
+
+    /// If enabled, pages will be scraped until an empty one is found, instead of relying on titles
+    /// counts reported by Filmweb
+    #[arg(long, value_parser, default_value_t = false)]
+    auto_paginate: bool,
+
+    /// Seconds without any scraped page or matched title, after which exporting is aborted. 0
+    /// disables it
+    #[arg(long, value_parser, default_value_t = 120)]
+    stall_timeout: u64,
+
+    /// If enabled, only favorited titles will be looked up at IMDb and exported
+    #[arg(long, value_parser, default_value_t = false)]
+    only_favorites: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints how many titles are rated and marked to see, without exporting anything
+    Count {
+        /// Print counts as JSON
+        #[arg(long, value_parser, default_value_t = false)]
+        json: bool,
+    },
 }
 
+/// Filmweb page along with type of its titles, if the page tells it
+type ScrapedPage = (Option<TitleType>, RatedPage);
+
+/// Page to scrape: its category, number, and number of pages in the category if it was precomputed
+type PageToScrape<'a> = (&'a UserPageType, u16, Option<u16>);
+
+/// Number of titles Filmweb shows on a single user's page
+const PER_PAGE: u16 = 25;
+
 lazy_static! {
     static ref ARGS: Args = Args::parse();
 }
 
+/// Snippets of anti-bot interstitial pages. Regular pages may contain some of them too (i.e. injected
+/// detection scripts), so they're looked for only in non-2xx responses
+const BOT_CHALLENGE_MARKERS: [&str; 4] = [
+    "cf-chl-",
+    "Just a moment...",
+    "Checking your browser",
+    "awsWafCookieDomainList",
+];
+
+/// Browser-like User-Agent for reachability checks, as anti-bot filters may flag clients without one
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:130.0) Gecko/20100101 Firefox/130.0";
+
+/// Bumped on every scraped page and every title matched at IMDb, watched by the watchdog
+static PROGRESS: AtomicUsize = AtomicUsize::new(0);
+static WATCHDOG_STOPPED: AtomicBool = AtomicBool::new(false);
+
 fn main() {
-    println!("{}", "filmweb-export starting...".yellow());
+    eprintln!("{}", "filmweb-export starting...".yellow());
     check_is_filmweb_reachable();
     env_logger::init();
 
-    let mut export_files = ExportFiles::default();
     let (token, session, jwt) = handle_empty_credentials(&ARGS);
     let user = FilmwebUser::new(token, session, jwt).expect("credentials ok");
 
-    // Get count of rated films, and convert it to number of pages
-    let movies_pages = user.num_of_rated_movies() / 25 + 1;
-    let shows_pages = user.num_of_rated_shows() / 25 + 1;
-    let watchlist_pages = user.num_of_watchlisted_titles() / 25 + 1;
-    let total_pages = movies_pages + shows_pages + watchlist_pages;
+    if let Some(Command::Count { json }) = ARGS.command {
+        print_counts(&user, json);
+        return;
+    }
+
+    if let Err(e) = ExportFiles::check(&ARGS) {
+        exit_on_export_files_error(&e);
+    }
+
+    let exported_pages: Arc<Mutex<Vec<ScrapedPage>>> = Arc::new(Mutex::new(Vec::new()));
 
-    let exported_pages: Arc<Mutex<Vec<RatedPage>>> = Arc::new(Mutex::new(Vec::with_capacity(total_pages as usize)));
+    check_is_imdb_reachable();
+    spawn_watchdog();
 
     let imdb_client = Arc::new(IMDb::new());
-    let (handle, tx) = imdb_scraping_thread(&Arc::clone(&exported_pages), total_pages, imdb_client);
+    let (handle, tx) = imdb_scraping_thread(&Arc::clone(&exported_pages), imdb_client);
+
+    let mut rng = ARGS
+        .shuffle
+        .then(|| ARGS.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64));
+
+    // Favorites apply to rated titles only
+    let pages_types: &[UserPageType] = if ARGS.only_favorites {
+        &[UserPageType::RatedFilms, UserPageType::RatedShows]
+    } else {
+        &[
+            UserPageType::RatedFilms,
+            UserPageType::RatedShows,
+            UserPageType::Watchlist,
+        ]
+    };
 
     // Scraping actual data from Filmweb
-    for (pages_count, page_type) in [
-        (movies_pages, UserPageType::RatedFilms),
-        (shows_pages, UserPageType::RatedShows),
-        (watchlist_pages, UserPageType::Watchlist),
-    ] {
-        scrape_fw(pages_count, &user, page_type, &Arc::clone(&tx)).unwrap();
+    if ARGS.auto_paginate {
+        for page_type in pages_types {
+            scrape_fw_until_empty(&user, page_type, &Arc::clone(&tx), rng.as_mut()).unwrap();
+        }
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(ARGS.threads as usize)
+            .build()
+            .unwrap();
+        let mut categories_pages: Vec<Vec<PageToScrape>> = pages_types
+            .iter()
+            .map(|page_type| {
+                let pages_count = pages_count(&user, page_type);
+                (1..=pages_count).map(|i| (page_type, i, Some(pages_count))).collect()
+            })
+            .collect();
+        // When shuffling, pages of all categories are shuffled together, so categories aren't
+        // requested one after another
+        if rng.is_some() {
+            categories_pages = vec![categories_pages.concat()];
+        }
+        for pages in categories_pages {
+            scrape_fw(&pool, pages, &user, &Arc::clone(&tx), rng.as_mut()).unwrap();
+        }
     }
+    // IMDb thread finishes once all pages sent to it are looked up
+    drop(tx);
 
     handle.join().unwrap();
 
+    // From now on it's waiting for the user, not for the network
+    WATCHDOG_STOPPED.store(true, Ordering::Relaxed);
+
+    // Created only now, so a run aborted while scraping leaves the previous export intact
+    let mut export_files = ExportFiles::new(&ARGS).unwrap_or_else(|e| exit_on_export_files_error(&e));
+
     // Check for possible false errors (in duration comparison only for now), and let the user
     // decide if it's a good match
-    for page in &mut *exported_pages.lock().unwrap() {
+    let mut verified_count = 0;
+    let mut unverified_count = 0;
+    for (title_type, page) in &mut *exported_pages.lock().unwrap() {
         for title in &mut *page.rated_titles {
             if title.imdb_data().is_some() {
                 let imdb_duration = title
@@ -89,16 +236,28 @@ fn main() {
                     .expect("imdb always have a duration");
                 let imdb_year = title.imdb_data().unwrap().year();
                 // TODO: move title.is_year_similar check to library
-                if title.is_duration_similar(u32::from(imdb_duration)) && title.is_year_similar(imdb_year) {
-                    title.to_csv_imdbv3_tmdb_files(&mut export_files);
+                let is_verified =
+                    title.is_duration_similar(u32::from(imdb_duration)) && title.is_year_similar(imdb_year);
+                if is_verified {
+                    verified_count += 1;
+                    export_files
+                        .write_title(title, *title_type)
+                        .expect("export files writable");
+                } else if ARGS.imdb_only_verified {
+                    // Unverified matches are treated as not found, without asking
+                    unverified_count += 1;
+                    drop(title.imdb_data_owned());
                 } else {
+                    unverified_count += 1;
                     let url = format!(
                         "https://www.imdb.com/title/{}",
                         title.imdb_data().expect("has imdb_data").id
                     );
                     let question = format!("{} Is {url} a good match for {}? (y/N): ", "[?]".blue(), title.title());
                     if user_agrees(question) {
-                        title.to_csv_imdbv3_tmdb_files(&mut export_files);
+                        export_files
+                            .write_title(title, *title_type)
+                            .expect("export files writable");
                     } else {
                         // Replace the title's imdb_data field Some(imdb_data) with None so it's marked
                         // as not found at IMDb
@@ -108,88 +267,210 @@ fn main() {
             }
         }
     }
+    export_files.flush().expect("export files writable");
     print_failed(&Arc::clone(&exported_pages));
+    print_summary(&Arc::clone(&exported_pages), verified_count, unverified_count);
+}
+
+fn print_counts(user: &FilmwebUser, json: bool) {
+    let movies = user.num_of_rated_movies();
+    let shows = user.num_of_rated_shows();
+    let marked_to_see = user.num_of_watchlisted_titles();
+    if json {
+        let counts = serde_json::json!({ "movies": movies, "shows": shows, "marked_to_see": marked_to_see });
+        println!("{counts}");
+    } else {
+        println!("movies: {movies}");
+        println!("shows: {shows}");
+        println!("marked_to_see: {marked_to_see}");
+    }
 }
 
+const TOKEN_COOKIE_NAME: &str = "_fwuser_token";
+const SESSION_COOKIE_NAME: &str = "_fwuser_sessionId";
+const JWT_COOKIE_NAME: &str = "JWT";
+
 fn handle_empty_credentials(args: &ARGS) -> (String, String, String) {
     let ask_for_cookie = |cookie_name: &'static str| -> String {
-        print!("{} {cookie_name} cookie value: ", "[?]".blue());
-        stdout().flush().expect("term ok");
+        eprint!("{} {cookie_name} cookie value: ", "[?]".blue());
+        stderr().flush().expect("term ok");
         let mut cookie = String::new();
         stdin().read_line(&mut cookie).expect("term ok");
-        cookie.trim().to_string()
+        cookie
     };
 
     let token = {
         if let Some(ref token) = args.token {
-            token.clone()
+            clean_cookie(token, TOKEN_COOKIE_NAME)
         } else {
-            ask_for_cookie("_fwuser_token")
+            clean_cookie(&ask_for_cookie(TOKEN_COOKIE_NAME), TOKEN_COOKIE_NAME)
         }
     };
 
     let session = {
         if let Some(ref session) = args.session {
-            session.clone()
+            clean_cookie(session, SESSION_COOKIE_NAME)
         } else {
-            ask_for_cookie("_fwuser_sessionId")
+            clean_cookie(&ask_for_cookie(SESSION_COOKIE_NAME), SESSION_COOKIE_NAME)
         }
     };
 
     let jwt = {
         if let Some(ref jwt) = args.jwt {
-            jwt.clone()
+            clean_cookie(jwt, JWT_COOKIE_NAME)
         } else {
-            ask_for_cookie("JWT")
+            clean_cookie(&ask_for_cookie(JWT_COOKIE_NAME), JWT_COOKIE_NAME)
         }
     };
 
     (token, session, jwt)
 }
 
-fn scrape_fw(
-    total_pages: u16,
+/// Extracts cookie value from what users copy out of the browser: surrounding whitespace, quotes
+/// and trailing semicolon are stripped, and so is a leading `name=`
+fn clean_cookie(cookie: &str, name: &str) -> String {
+    let mut cookie = cookie.trim().trim_end_matches(';').trim();
+    if let Some(value) = cookie
+        .strip_prefix(name)
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+    {
+        cookie = value.trim();
+    }
+    cookie.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+fn exit_on_export_files_error(e: &std::io::Error) -> ! {
+    eprintln!(
+        "{} Couldn't create export files in {}: {e}",
+        "[!]".red(),
+        ARGS.output.display()
+    );
+    std::process::exit(1);
+}
+
+/// Keeps scraping pages in batches of --threads pages, until Filmweb returns an empty page
+fn scrape_fw_until_empty(
     user: &FilmwebUser,
-    titles_type: UserPageType,
-    tx: &Mutex<Sender<RatedPage>>,
+    titles_type: &UserPageType,
+    tx: &Mutex<Sender<ScrapedPage>>,
+    mut rng: Option<&mut StdRng>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // just to print out what is being scraped
-    let what = match titles_type {
-        UserPageType::RatedFilms => "films",
-        UserPageType::RatedShows => "serials",
-        UserPageType::Watchlist => "wants2see",
-    };
-    let page_type = Arc::new(&titles_type);
-    let error_happened = Arc::new(AtomicBool::new(false));
-    let scraped_pages_count = Arc::new(AtomicUsize::new(0));
+    // Page number is u8 in UserPage, so it's a hard limit anyway
+    let last_page = ARGS.max_pages.unwrap_or(u16::from(u8::MAX));
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(ARGS.threads as usize)
         .build()?;
+    let mut first_page = 1;
+    while first_page <= last_page {
+        let batch_last_page = last_page.min(first_page + u16::from(ARGS.threads) - 1);
+        let pages = (first_page..=batch_last_page).map(|i| (titles_type, i, None)).collect();
+        if scrape_fw(&pool, pages, user, tx, rng.as_deref_mut())? {
+            return Ok(());
+        }
+        first_page = batch_last_page + 1;
+    }
+    eprintln!(
+        "{} Reached page {last_page} of {} without finding an empty page, stopping",
+        "[!]".yellow(),
+        category_name(titles_type)
+    );
+    Ok(())
+}
+
+/// Scrapes given pages, and sends the non-empty ones to IMDb thread. Returns whether an empty page
+/// was encountered. Pages may be of different categories
+fn scrape_fw(
+    pool: &rayon::ThreadPool,
+    pages: Vec<PageToScrape<'_>>,
+    user: &FilmwebUser,
+    tx: &Mutex<Sender<ScrapedPage>>,
+    rng: Option<&mut StdRng>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let error_happened = Arc::new(AtomicBool::new(false));
+    let empty_page_found = &AtomicBool::new(false);
+    let per_page_warned = &AtomicBool::new(false);
+    let scraped_pages_count = Arc::new(AtomicUsize::new(0));
+    // Progress is shown out of all given pages, only if all of them were precomputed
+    let total_pages = pages
+        .iter()
+        .all(|(_, _, category_pages)| category_pages.is_some())
+        .then_some(pages.len());
+    // Pages, each with a seed its titles are shuffled with when --shuffle is enabled. Seeds are drawn
+    // upfront so the order doesn't depend on threads scheduling
+    let mut pages: Vec<(PageToScrape, Option<u64>)> = pages.into_iter().map(|page| (page, None)).collect();
+    if let Some(rng) = rng {
+        pages.shuffle(rng);
+        for (_, titles_seed) in &mut pages {
+            *titles_seed = Some(rng.gen());
+        }
+    }
     pool.scope(|s| {
-        for i in 1..=total_pages {
+        for ((titles_type, i, category_pages), titles_seed) in pages {
             let scraped_pages_count = scraped_pages_count.clone();
-            let page_type = Arc::clone(&page_type);
             let error_happened = Arc::clone(&error_happened);
             s.spawn(move |_| {
-                let page_type = match *page_type {
+                // just to print out what is being scraped
+                let what = category_name(titles_type);
+                let title_type = match titles_type {
+                    UserPageType::RatedFilms => Some(TitleType::Movie),
+                    UserPageType::RatedShows => Some(TitleType::Show),
+                    UserPageType::Watchlist => None,
+                };
+                let page_type = match titles_type {
                     UserPageType::RatedFilms => UserPage::RatedFilms(i as u8),
                     UserPageType::RatedShows => UserPage::RatedShows(i as u8),
                     UserPageType::Watchlist => UserPage::Watchlist(i as u8),
                 };
-                let mut fw_page = user.scrape(page_type);
+                let fw_page = panic::catch_unwind(AssertUnwindSafe(|| user.scrape(page_type)));
+                // Filmweb may start serving an anti-bot challenge mid-run, which filmed can't parse
+                if !matches!(fw_page, Ok(Ok(_))) {
+                    check_is_filmweb_reachable();
+                }
+                let Ok(mut fw_page) = fw_page else {
+                    eprintln!("{} Scraping page {i} of {what} panicked", "error occured:".red());
+                    std::process::exit(1);
+                };
                 if let Err(e) = fw_page.as_mut() {
                     eprintln!("{} {e}", "error occured:".red());
                     error_happened.store(false, Ordering::Relaxed);
                     std::process::exit(1);
                 };
-                tx.lock().unwrap().send(fw_page.unwrap()).unwrap();
+                let mut fw_page = fw_page.unwrap();
+                PROGRESS.fetch_add(1, Ordering::Relaxed);
+                // Only the last page may be incomplete, otherwise Filmweb changed its pagination and
+                // the pages count is off
+                let titles_count = fw_page.rated_titles.len();
+                if titles_count == 0 {
+                    empty_page_found.store(true, Ordering::Relaxed);
+                    return;
+                }
+                if category_pages.is_some_and(|category_pages| i < category_pages)
+                    && titles_count != PER_PAGE as usize
+                    && !per_page_warned.swap(true, Ordering::Relaxed)
+                {
+                    eprintln!(
+                        "{} Page {i} of {what} has {titles_count} titles instead of {PER_PAGE}, some titles may be missed",
+                        "[!]".yellow()
+                    );
+                }
+                if ARGS.only_favorites {
+                    fw_page.rated_titles.retain(|title| title.is_favorited());
+                }
+                if let Some(titles_seed) = titles_seed {
+                    fw_page.rated_titles.shuffle(&mut StdRng::seed_from_u64(titles_seed));
+                }
+                tx.lock().unwrap().send((title_type, fw_page)).unwrap();
                 scraped_pages_count.store(scraped_pages_count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
-                println!(
-                    "{} Scraping {what}... [{}/{total_pages}]",
-                    "[i]".blue(),
-                    scraped_pages_count.load(Ordering::Relaxed)
-                );
-                stdout().flush().expect("term ok");
+                if let Some(total_pages) = total_pages {
+                    eprintln!(
+                        "{} Scraping {what}... [{}/{total_pages}]",
+                        "[i]".blue(),
+                        scraped_pages_count.load(Ordering::Relaxed)
+                    );
+                } else {
+                    eprintln!("{} Scraping {what}... [page {i}]", "[i]".blue());
+                }
+                stderr().flush().expect("term ok");
             });
         }
     });
@@ -198,13 +479,47 @@ fn scrape_fw(
         eprintln!("{}", "Exiting due to some thread(s) reporting error(s)".red());
         std::process::exit(1);
     }
-    Ok(())
+    Ok(empty_page_found.load(Ordering::Relaxed))
+}
+
+fn category_name(titles_type: &UserPageType) -> &'static str {
+    match titles_type {
+        UserPageType::RatedFilms => "films",
+        UserPageType::RatedShows => "serials",
+        UserPageType::Watchlist => "wants2see",
+    }
+}
+
+/// Gets count of titles of a category, and converts it to number of pages
+fn pages_count(user: &FilmwebUser, titles_type: &UserPageType) -> u16 {
+    let titles_count = match titles_type {
+        UserPageType::RatedFilms => user.num_of_rated_movies(),
+        UserPageType::RatedShows => user.num_of_rated_shows(),
+        UserPageType::Watchlist => user.num_of_watchlisted_titles(),
+    };
+    capped_pages_count(titles_count.div_ceil(PER_PAGE), titles_type)
+}
+
+/// Bounds pages count of a category by --max-pages, so a bogus count won't make us scrape
+/// thousands of empty pages
+fn capped_pages_count(pages_count: u16, titles_type: &UserPageType) -> u16 {
+    match ARGS.max_pages {
+        Some(max_pages) if pages_count > max_pages => {
+            eprintln!(
+                "{} Filmweb reports {pages_count} pages of {}, only {max_pages} will be scraped (--max-pages)",
+                "[!]".yellow(),
+                category_name(titles_type)
+            );
+            max_pages
+        }
+        _ => pages_count,
+    }
 }
 
 fn user_agrees(question: impl Display) -> bool {
     loop {
-        print!("{question}");
-        std::io::stdout().flush().expect("term ok");
+        eprint!("{question}");
+        std::io::stderr().flush().expect("term ok");
         let mut decision = String::new();
         stdin().read_line(&mut decision).expect("term ok");
         decision = decision.trim().to_lowercase();
@@ -213,17 +528,16 @@ fn user_agrees(question: impl Display) -> bool {
         } else if decision == "n" || decision == "no" || decision.is_empty() {
             return false;
         }
-        println!("{} Not understood", "[?]".yellow());
+        eprintln!("{} Not understood", "[?]".yellow());
     }
 }
 
+/// Looks up pages' titles at IMDb as they are received, until all senders are dropped
 fn imdb_scraping_thread(
-    exported_pages: &Arc<Mutex<Vec<RatedPage>>>,
-    pages_count: u16,
+    exported_pages: &Arc<Mutex<Vec<ScrapedPage>>>,
     imdb_client: Arc<IMDb>,
-) -> (JoinHandle<()>, Arc<Mutex<Sender<RatedPage>>>) {
-    let (tx, rx) = flume::unbounded::<RatedPage>();
-    let rx = Arc::new(Mutex::new(rx));
+) -> (JoinHandle<()>, Arc<Mutex<Sender<ScrapedPage>>>) {
+    let (tx, rx) = flume::unbounded::<ScrapedPage>();
     let tx = Arc::new(Mutex::new(tx));
     let exported_pages_clone = Arc::clone(exported_pages);
     let handle = thread::spawn(move || {
@@ -232,18 +546,21 @@ fn imdb_scraping_thread(
             .build()
             .unwrap();
         pool.scope(|s| {
-            for _ in 0..pages_count {
-                let mut page = rx.lock().unwrap().recv().unwrap();
+            for (title_type, mut page) in rx.iter() {
                 let exported_pages_clone = Arc::clone(&exported_pages_clone);
                 let imdb_client_clone = Arc::clone(&imdb_client);
                 s.spawn(move |_| {
                     for title in &mut page.rated_titles {
                         title.set_imdb_data_with_lookup(&imdb_client_clone).ok();
+                        // Failed lookups don't count, they're exactly what a ban looks like
+                        if title.imdb_data().is_some() {
+                            PROGRESS.fetch_add(1, Ordering::Relaxed);
+                        }
                         if !ARGS.quiet {
                             print_title(title);
                         }
                     }
-                    exported_pages_clone.lock().unwrap().push(page);
+                    exported_pages_clone.lock().unwrap().push((title_type, page));
                 });
             }
         });
@@ -251,9 +568,40 @@ fn imdb_scraping_thread(
     (handle, tx)
 }
 
-fn print_failed(pages: &Arc<Mutex<Vec<RatedPage>>>) {
-    println!("Following titles couldn't be found:");
-    for page in &*pages.lock().unwrap() {
+/// Aborts the whole run if no progress is made for --stall-timeout seconds, until the watchdog is
+/// stopped
+fn spawn_watchdog() {
+    if ARGS.stall_timeout == 0 {
+        return;
+    }
+    let stall_timeout = Duration::from_secs(ARGS.stall_timeout);
+    thread::spawn(move || {
+        let mut last_progress = PROGRESS.load(Ordering::Relaxed);
+        let mut last_progress_at = Instant::now();
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            if WATCHDOG_STOPPED.load(Ordering::Relaxed) {
+                return;
+            }
+            let progress = PROGRESS.load(Ordering::Relaxed);
+            if progress != last_progress {
+                last_progress = progress;
+                last_progress_at = Instant::now();
+            } else if last_progress_at.elapsed() >= stall_timeout {
+                eprintln!(
+                    "{} No progress for {}s, likely blocked or Filmweb's markup changed. Try again later, or with RUST_LOG=debug to see what's going on",
+                    "[!]".red(),
+                    stall_timeout.as_secs()
+                );
+                std::process::exit(1);
+            }
+        }
+    });
+}
+
+fn print_failed(pages: &Arc<Mutex<Vec<ScrapedPage>>>) {
+    eprintln!("Following titles couldn't be found:");
+    for (_, page) in &*pages.lock().unwrap() {
         for title in &page.rated_titles {
             if title.imdb_data().is_none() {
                 print_title(title);
@@ -262,6 +610,40 @@ fn print_failed(pages: &Arc<Mutex<Vec<RatedPage>>>) {
     }
 }
 
+/// Prints match rate, along with how many matches were verified by year and duration, so both
+/// matching policies can be compared from a single run
+fn print_summary(pages: &Arc<Mutex<Vec<ScrapedPage>>>, verified_count: usize, unverified_count: usize) {
+    let pages = pages.lock().unwrap();
+    let titles = pages.iter().flat_map(|(_, page)| &page.rated_titles);
+    let total_count = titles.clone().count();
+    let matched_count = titles.filter(|title| title.imdb_data().is_some()).count();
+    let match_rate = if total_count == 0 {
+        0.0
+    } else {
+        matched_count as f64 / total_count as f64 * 100.0
+    };
+    let policy = if ARGS.imdb_only_verified {
+        "verified only"
+    } else {
+        "default"
+    };
+    eprintln!(
+        "{} Matched {matched_count}/{total_count} titles at IMDb ({match_rate:.1}%, {policy})",
+        "[i]".blue()
+    );
+    if ARGS.imdb_only_verified {
+        eprintln!(
+            "{} {verified_count} verified, {unverified_count} dropped as unverified (would have been asked under default)",
+            "[i]".blue()
+        );
+    } else {
+        eprintln!(
+            "{} {verified_count} verified, {unverified_count} asked about (would have been dropped with --imdb-only-verified)",
+            "[i]".blue()
+        );
+    }
+}
+
 fn print_title<T: RatedTitle + IMDbLookup>(fw_title: &T) {
     let print_rating = || {
         if fw_title.is_favorited() {
@@ -285,11 +667,11 @@ fn print_title<T: RatedTitle + IMDbLookup>(fw_title: &T) {
         let separator = "|".dimmed();
         let imdb_name = imdb_name.dimmed();
         let imdb_title_url = format!("{}{}", "https://imdb.com/title/".dimmed(), imdb_id.to_string().dimmed());
-        println!("{add_prefix} {title_name} {title_year} {rating} {separator} {imdb_name} {imdb_title_url}");
+        eprintln!("{add_prefix} {title_name} {title_year} {rating} {separator} {imdb_name} {imdb_title_url}");
     };
 
     let print_not_found = || {
-        println!("{} {} {}", "[-]".red(), fw_title.title(), print_rating());
+        eprintln!("{} {} {}", "[-]".red(), fw_title.title(), print_rating());
     };
 
     fw_title.imdb_data().map_or_else(print_not_found, |imdb_data| {
@@ -297,22 +679,99 @@ fn print_title<T: RatedTitle + IMDbLookup>(fw_title: &T) {
     });
 }
 
+/// Exits if Filmweb is down or serves an anti-bot challenge
 fn check_is_filmweb_reachable() {
     let prefix = "[!]".red();
-    match reqwest::blocking::get("https://www.filmweb.pl") {
+    match preflight_get("https://www.filmweb.pl") {
         Ok(res) => {
-            if res.status().is_success() {
-            } else if res.status().is_server_error() {
-                println!(
-                    "{prefix} Filmweb's servers are experiencing some issues, try again later. Status: {:?}",
-                    res.status()
+            let status = res.status();
+            if is_bot_challenge(res) {
+                eprintln!(
+                    "{prefix} Filmweb seems to serve an anti-bot challenge instead of its content. Try again later, or slow down with fewer --threads and --shuffle"
+                );
+                std::process::exit(1);
+            }
+            if status.is_success() {
+            } else if status.is_server_error() {
+                eprintln!(
+                    "{prefix} Filmweb's servers are experiencing some issues, try again later. Status: {status:?}"
                 );
                 std::process::exit(1);
             }
         }
         Err(e) => {
-            println!("{prefix} Couldn't connect to Filmweb. Error: {:?}", e);
+            eprintln!("{prefix} Couldn't connect to Filmweb. Error: {:?}", e);
             std::process::exit(1);
         }
     };
 }
+
+/// Unlike Filmweb's, IMDb's challenge only makes some lookups fail, so it's just a warning
+fn check_is_imdb_reachable() {
+    if let Ok(res) = preflight_get("https://www.imdb.com") {
+        if is_bot_challenge(res) {
+            eprintln!(
+                "{} IMDb seems to serve an anti-bot challenge instead of its content, titles may not be found. Try again later, or slow down with fewer --threads",
+                "[!]".yellow()
+            );
+        }
+    }
+}
+
+/// Requests a page the way a browser would, though without any cookies, so a challenge may still be
+/// served to scraping clients only, or the other way round
+fn preflight_get(url: &str) -> reqwest::Result<reqwest::blocking::Response> {
+    reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .build()?
+        .get(url)
+        .header(reqwest::header::ACCEPT_LANGUAGE, "pl,en;q=0.8")
+        .send()
+}
+
+/// Tells if the response is an anti-bot interstitial (Cloudflare, AWS WAF and alike) instead of
+/// actual content
+fn is_bot_challenge(res: reqwest::blocking::Response) -> bool {
+    let headers = res.headers();
+    if headers.get("cf-mitigated").is_some_and(|value| value == "challenge")
+        || headers.contains_key("x-amzn-waf-action")
+    {
+        return true;
+    }
+    if res.status().is_success() {
+        return false;
+    }
+    let body = res.text().unwrap_or_default();
+    BOT_CHALLENGE_MARKERS.iter().any(|marker| body.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_cookie_strips_what_is_copied_along() {
+        let cases = [
+            ("x", "x"),
+            ("  x\n", "x"),
+            ("JWT=x", "x"),
+            ("JWT=\"x\";", "x"),
+            ("'x'", "x"),
+            ("JWT = x ;", "x"),
+            ("JWT= 'x'", "x"),
+        ];
+        for (cookie, expected) in cases {
+            assert_eq!(clean_cookie(cookie, JWT_COOKIE_NAME), expected, "cookie: {cookie:?}");
+        }
+    }
+
+    #[test]
+    fn clean_cookie_keeps_other_cookies_names() {
+        assert_eq!(clean_cookie("_fwuser_token=x", JWT_COOKIE_NAME), "_fwuser_token=x");
+        assert_eq!(clean_cookie("JWTx=y", JWT_COOKIE_NAME), "JWTx=y");
+        assert_eq!(
+            clean_cookie("_fwuser_sessionIdx=y", SESSION_COOKIE_NAME),
+            "_fwuser_sessionIdx=y"
+        );
+    }
+}